@@ -1,9 +1,9 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Result;
-use clap::ArgMatches;
+use clap::{ArgMatches, ValueEnum};
 use config::builder::DefaultState;
-use config::{ConfigError, File, FileFormat};
+use config::{ConfigError, File, FileFormat, Map, Source, Value, ValueKind};
 use payjoin::bitcoin::FeeRate;
 use serde::Deserialize;
 use url::Url;
@@ -12,6 +12,81 @@ use crate::db;
 
 type Builder = config::builder::ConfigBuilder<DefaultState>;
 
+/// Prefix used to recognize environment variables as payjoin-cli config overrides
+const ENV_PREFIX: &str = "PAYJOIN_";
+
+/// The Bitcoin network to connect to, mirroring ord's `Chain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Chain {
+    Bitcoin,
+    Testnet,
+    Signet,
+    Regtest,
+}
+
+impl Chain {
+    /// The lowercase name used both as the clap value and the config key
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Bitcoin => "bitcoin",
+            Self::Testnet => "testnet",
+            Self::Signet => "signet",
+            Self::Regtest => "regtest",
+        }
+    }
+
+    /// bitcoind's default `rpcport` for this chain
+    fn default_rpc_port(self) -> u16 {
+        match self {
+            Self::Bitcoin => 8332,
+            Self::Testnet => 18332,
+            Self::Signet => 38332,
+            Self::Regtest => 18443,
+        }
+    }
+
+    /// The subdirectory bitcoind nests this chain's data under, relative to its
+    /// `-datadir` (mainnet has none; the others match bitcoind's own naming)
+    fn data_dir_suffix(self) -> Option<&'static str> {
+        match self {
+            Self::Bitcoin => None,
+            Self::Testnet => Some("testnet3"),
+            Self::Signet => Some("signet"),
+            Self::Regtest => Some("regtest"),
+        }
+    }
+
+    /// Locate bitcoind's `.cookie` file for this chain under its default data
+    /// directory, the way ord's `Chain` resolves `bitcoin.conf`-adjacent files.
+    fn cookie_path(self) -> Option<PathBuf> {
+        let bitcoin_dir = default_bitcoin_dir()?;
+        let dir = match self.data_dir_suffix() {
+            Some(suffix) => bitcoin_dir.join(suffix),
+            None => bitcoin_dir,
+        };
+        let cookie = dir.join(".cookie");
+        cookie.exists().then_some(cookie)
+    }
+}
+
+/// bitcoind's default `-datadir`, which (unlike our own config dir) follows
+/// bitcoind's own per-platform convention rather than the XDG/known-folders one
+/// `dirs::config_dir` resolves to.
+fn default_bitcoin_dir() -> Option<PathBuf> {
+    if cfg!(target_os = "windows") {
+        dirs::data_dir().map(|dir| dir.join("Bitcoin"))
+    } else if cfg!(target_os = "macos") {
+        dirs::home_dir().map(|dir| dir.join("Library/Application Support/Bitcoin"))
+    } else {
+        dirs::home_dir().map(|dir| dir.join(".bitcoin"))
+    }
+}
+
+impl Default for Chain {
+    fn default() -> Self { Self::Regtest }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct BitcoindConfig {
     pub rpchost: Url,
@@ -36,22 +111,76 @@ pub struct V2Config {
     pub pj_directory: Url,
 }
 
+#[cfg(feature = "lightning")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct LnConfig {
+    /// REST address of the Lightning node (LND's REST gateway or CLN's REST plugin)
+    pub node_address: Url,
+    /// Path to LND's `tls.cert`, as used by nolooking's `conf.template`; when
+    /// `macaroon_path` is unset, the path to a CLN rune instead
+    pub cert_path: Option<PathBuf>,
+    /// Path to an LND macaroon; setting it selects LND, leaving it unset selects CLN
+    pub macaroon_path: Option<PathBuf>,
+    /// Hex-encoded node ID of the peer to open the channel with (LND's `node_pubkey`,
+    /// CLN's `id`) - the node configured above is only the wallet funding the channel,
+    /// not necessarily the counterparty we're opening it with.
+    pub peer_pubkey: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ControlConfig {
+    /// Either a loopback socket address (`127.0.0.1:3100`) or a `unix:` URI pointing
+    /// at a socket path, mirroring OpenEthereum's `rpc_client`/`rpc_cli` split: this
+    /// process hosts the JSON-RPC server, and a separate CLI subcommand connects to
+    /// it as a plain client.
+    ///
+    /// `listsessions`/`getoriginalpsbt`/`abortsession` are unauthenticated beyond
+    /// `auth_token` below, and `getoriginalpsbt` reveals the receiver's chosen UTXOs -
+    /// exactly what payjoin exists to hide. The default binds to loopback only, but
+    /// nothing stops this from being pointed at a non-loopback address for a
+    /// daemonized setup; doing so without setting `auth_token` hands anyone who can
+    /// reach the socket read/abort access to every session.
+    pub bind: String,
+    /// Shared secret required in the `auth_token` field of every control RPC
+    /// request when set. Unset (the default) leaves `bind` unauthenticated, which is
+    /// fine for the loopback-only default but should always be set if `bind` is ever
+    /// pointed at a non-loopback address.
+    pub auth_token: Option<String>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub db_path: PathBuf,
     pub max_fee_rate: Option<FeeRate>,
+    pub chain: Chain,
     pub bitcoind: BitcoindConfig,
+    pub control: ControlConfig,
     #[cfg(feature = "v1")]
     pub v1: V1Config,
     #[cfg(feature = "v2")]
     pub v2: V2Config,
+    #[cfg(feature = "lightning")]
+    pub ln: Option<LnConfig>,
 }
 
 impl Config {
     pub(crate) fn new(matches: &ArgMatches) -> Result<Self, ConfigError> {
+        let data_dir = resolve_data_dir(matches);
+        let config_path = resolve_config_path(matches);
+
+        // `chain` and whether bitcoind auth was already configured both have to be
+        // known before we can pick the right defaults below, and both can come from
+        // `config.toml` or a `PAYJOIN_` env var as easily as from the CLI - so probe
+        // the fully layered (CLI + file + env) config first rather than checking
+        // `matches` alone.
+        let (chain, has_explicit_bitcoind_auth) = probe_bitcoind_auth(matches, &config_path)?;
+
         let mut builder = config::Config::builder();
-        builder = add_bitcoind_defaults(builder, matches)?;
-        builder = add_common_defaults(builder, matches)?;
+        builder = builder
+            .set_default("chain", Chain::default().as_str())?
+            .set_override_option("chain", matches.get_one::<Chain>("chain").map(|c| c.as_str()))?;
+        builder = add_bitcoind_defaults(builder, matches, chain, has_explicit_bitcoind_auth)?;
+        builder = add_common_defaults(builder, matches, &data_dir)?;
 
         #[cfg(feature = "v1")]
         {
@@ -63,8 +192,14 @@ impl Config {
             builder = add_v2_defaults(builder, matches)?;
         }
 
+        #[cfg(feature = "lightning")]
+        {
+            builder = add_ln_defaults(builder, matches)?;
+        }
+
         builder = handle_subcommands(builder, matches)?;
-        builder = builder.add_source(File::new("config.toml", FileFormat::Toml).required(false));
+        builder = builder.add_source(File::from(config_path).format(FileFormat::Toml).required(false));
+        builder = add_env_defaults(builder)?;
 
         let config = builder.build()?;
         let app_config: Config = config.try_deserialize()?;
@@ -73,36 +208,156 @@ impl Config {
     }
 }
 
-/// Set up default values and CLI overrides for Bitcoin RPC connection settings
-fn add_bitcoind_defaults(builder: Builder, matches: &ArgMatches) -> Result<Builder, ConfigError> {
-    builder
-        .set_default("bitcoind.rpchost", "http://localhost:18443")?
-        .set_override_option(
-            "bitcoind.rpchost",
-            matches.get_one::<Url>("rpchost").map(|s| s.as_str()),
-        )?
-        .set_default("bitcoind.cookie", None::<String>)?
+/// Resolve `chain` and whether bitcoind auth (a cookie or rpcuser/rpcpassword) is
+/// already configured anywhere - CLI, `config.toml`, or a `PAYJOIN_` env var - ahead
+/// of setting the real defaults, since both the RPC port default and cookie
+/// auto-discovery depend on them. This runs its own small `build()` over the CLI
+/// overrides for these keys, the config file and the env layer, so it reflects the
+/// same config the final build will see.
+fn probe_bitcoind_auth(
+    matches: &ArgMatches,
+    config_path: &Path,
+) -> Result<(Chain, bool), ConfigError> {
+    let mut builder = config::Config::builder()
+        .set_default("chain", Chain::default().as_str())?
+        .set_override_option("chain", matches.get_one::<Chain>("chain").map(|c| c.as_str()))?
         .set_override_option(
             "bitcoind.cookie",
             matches.get_one::<String>("cookie_file").map(|s| s.as_str()),
         )?
-        .set_default("bitcoind.rpcuser", "bitcoin")?
         .set_override_option(
             "bitcoind.rpcuser",
             matches.get_one::<String>("rpcuser").map(|s| s.as_str()),
         )?
-        .set_default("bitcoind.rpcpassword", "")?
         .set_override_option(
             "bitcoind.rpcpassword",
             matches.get_one::<String>("rpcpassword").map(|s| s.as_str()),
-        )
+        )?
+        .add_source(File::from(config_path.to_path_buf()).format(FileFormat::Toml).required(false));
+    builder = add_env_defaults(builder)?;
+    let probe = builder.build()?;
+
+    let chain = probe
+        .get_string("chain")
+        .ok()
+        .and_then(|s| Chain::from_str(&s, true).ok())
+        .unwrap_or_default();
+    let has_explicit_auth = probe.get_string("bitcoind.cookie").is_ok()
+        || probe.get_string("bitcoind.rpcuser").is_ok()
+        || probe.get_string("bitcoind.rpcpassword").is_ok();
+
+    Ok((chain, has_explicit_auth))
+}
+
+/// Set up default values and CLI overrides for Bitcoin RPC connection settings
+fn add_bitcoind_defaults(
+    builder: Builder,
+    matches: &ArgMatches,
+    chain: Chain,
+    has_explicit_auth: bool,
+) -> Result<Builder, ConfigError> {
+    let rpcuser = matches.get_one::<String>("rpcuser");
+    let rpcpassword = matches.get_one::<String>("rpcpassword");
+    let cookie_file = matches.get_one::<String>("cookie_file");
+
+    // Only fall back to cookie auto-discovery when no auth was configured anywhere
+    // (CLI, config.toml, or env - per `probe_bitcoind_auth`); otherwise leave the
+    // default unset so the override below (or the rpcuser/rpcpassword defaults,
+    // which are themselves overridden by the file/env layers later) take precedence.
+    let default_cookie = if has_explicit_auth {
+        None
+    } else {
+        Some(chain.cookie_path().ok_or_else(|| {
+            ConfigError::Message(format!(
+                "no bitcoind.cookie, rpcuser/rpcpassword configured and no {} cookie file found; \
+                 set bitcoind.cookie or bitcoind.rpcuser/rpcpassword (via CLI flags, config.toml, \
+                 or PAYJOIN_BITCOIND_* env vars), or run bitcoind for this chain",
+                chain.as_str()
+            ))
+        })?)
+    };
+
+    builder
+        .set_default(
+            "bitcoind.rpchost",
+            format!("http://localhost:{}", chain.default_rpc_port()),
+        )?
+        .set_override_option(
+            "bitcoind.rpchost",
+            matches.get_one::<Url>("rpchost").map(|s| s.as_str()),
+        )?
+        .set_default(
+            "bitcoind.cookie",
+            default_cookie.map(|p| p.to_string_lossy().into_owned()),
+        )?
+        .set_override_option("bitcoind.cookie", cookie_file.map(|s| s.as_str()))?
+        .set_default("bitcoind.rpcuser", "bitcoin")?
+        .set_override_option("bitcoind.rpcuser", rpcuser.map(|s| s.as_str()))?
+        .set_default("bitcoind.rpcpassword", "")?
+        .set_override_option("bitcoind.rpcpassword", rpcpassword.map(|s| s.as_str()))
 }
 
 /// Set up default values and CLI overrides for common settings shared between v1 and v2
-fn add_common_defaults(builder: Builder, matches: &ArgMatches) -> Result<Builder, ConfigError> {
+fn add_common_defaults(
+    builder: Builder,
+    matches: &ArgMatches,
+    data_dir: &Path,
+) -> Result<Builder, ConfigError> {
+    let db_path = data_dir.join(db::DB_PATH);
     builder
-        .set_default("db_path", db::DB_PATH)?
-        .set_override_option("db_path", matches.get_one::<String>("db_path").map(|s| s.as_str()))
+        .set_default("db_path", db_path.to_string_lossy().into_owned())?
+        .set_override_option("db_path", matches.get_one::<String>("db_path").map(|s| s.as_str()))?
+        .set_default("control.bind", "127.0.0.1:3100")?
+        .set_override_option(
+            "control.bind",
+            matches.get_one::<String>("control_bind").map(|s| s.as_str()),
+        )?
+        .set_default("control.auth_token", None::<String>)?
+        .set_override_option(
+            "control.auth_token",
+            matches.get_one::<String>("control_auth_token").map(|s| s.as_str()),
+        )
+}
+
+/// Resolve the directory that holds the database and, absent `--config`, `config.toml`.
+///
+/// `--data-dir` always wins; otherwise we fall back to the platform's default config
+/// directory (as ord's settings loader does), and finally to the current working
+/// directory if the platform has no notion of one.
+fn resolve_data_dir(matches: &ArgMatches) -> PathBuf {
+    matches
+        .get_one::<PathBuf>("data_dir")
+        .cloned()
+        .or_else(|| dirs::config_dir().map(|dir| dir.join("payjoin-cli")))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Resolve the effective config file path: `--config` always wins outright.
+/// Otherwise look for `config.toml` under `--data-dir`, then the platform's default
+/// config directory, and finally the current working directory - preserving the
+/// prior `./config.toml` behavior for deployments that never set `--data-dir`. The
+/// first of these that actually exists is used; if none do, we still point at the
+/// highest-priority candidate (`--data-dir` if given, else the platform config dir,
+/// else CWD) so a later `payjoin-cli init`-style write has somewhere sensible to go.
+fn resolve_config_path(matches: &ArgMatches) -> PathBuf {
+    if let Some(config) = matches.get_one::<PathBuf>("config") {
+        return config.clone();
+    }
+
+    let candidates: Vec<PathBuf> = [
+        matches.get_one::<PathBuf>("data_dir").map(|dir| dir.join("config.toml")),
+        dirs::config_dir().map(|dir| dir.join("payjoin-cli").join("config.toml")),
+        Some(PathBuf::from("config.toml")),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    candidates
+        .iter()
+        .find(|path| path.exists())
+        .cloned()
+        .unwrap_or_else(|| candidates[0].clone())
 }
 
 /// Set up default values for v1-specific settings when v2 is not enabled
@@ -125,6 +380,32 @@ fn add_v2_defaults(builder: Builder, matches: &ArgMatches) -> Result<Builder, Co
         .set_default("v2.ohttp_keys", None::<String>)
 }
 
+/// Set up default values and CLI overrides for the optional Lightning subsystem.
+///
+/// There is no `ln.node_address` default: absent an override or a `[ln]` section in
+/// `config.toml`, `Config::ln` deserializes to `None` and payjoin-to-channel-open is
+/// simply disabled.
+#[cfg(feature = "lightning")]
+fn add_ln_defaults(builder: Builder, matches: &ArgMatches) -> Result<Builder, ConfigError> {
+    builder
+        .set_override_option(
+            "ln.node_address",
+            matches.get_one::<Url>("ln_node_address").map(|s| s.as_str()),
+        )?
+        .set_override_option(
+            "ln.cert_path",
+            matches.get_one::<String>("ln_cert_path").map(|s| s.as_str()),
+        )?
+        .set_override_option(
+            "ln.macaroon_path",
+            matches.get_one::<String>("ln_macaroon_path").map(|s| s.as_str()),
+        )?
+        .set_override_option(
+            "ln.peer_pubkey",
+            matches.get_one::<String>("ln_peer_pubkey").map(|s| s.as_str()),
+        )
+}
+
 /// Handles configuration overrides based on CLI subcommands
 fn handle_subcommands(builder: Builder, matches: &ArgMatches) -> Result<Builder, ConfigError> {
     match matches.subcommand() {
@@ -136,6 +417,7 @@ fn handle_subcommands(builder: Builder, matches: &ArgMatches) -> Result<Builder,
         }
         #[cfg(feature = "v2")]
         Some(("resume", _)) => Ok(builder),
+        Some(("control", _)) => Ok(builder),
         _ => unreachable!(), // If all subcommands are defined above, anything else is unreachabe!()
     }
 }
@@ -171,6 +453,121 @@ fn handle_receive_command(builder: Builder, matches: &ArgMatches) -> Result<Buil
     Ok(builder)
 }
 
+/// The dotted config keys `PAYJOIN_`-prefixed env vars are allowed to set. Built
+/// explicitly (rather than via a blind separator-to-dot substitution) because
+/// several leaves themselves contain underscores (`ohttp_relay`, `pj_directory`,
+/// `macaroon_path`, ...) and a naive `_` -> `.` replace would mangle them, e.g.
+/// turning `v2.ohttp_relay` into `v2.ohttp.relay`.
+fn known_env_keys() -> Vec<&'static str> {
+    #[allow(unused_mut)]
+    let mut keys = vec![
+        "db_path",
+        "max_fee_rate",
+        "chain",
+        "bitcoind.rpchost",
+        "bitcoind.cookie",
+        "bitcoind.rpcuser",
+        "bitcoind.rpcpassword",
+        "control.bind",
+        "control.auth_token",
+    ];
+    #[cfg(feature = "v1")]
+    keys.extend(["v1.port", "v1.pj_endpoint"]);
+    #[cfg(feature = "v2")]
+    keys.extend(["v2.ohttp_keys", "v2.ohttp_relay", "v2.pj_directory"]);
+    #[cfg(feature = "lightning")]
+    keys.extend(["ln.node_address", "ln.cert_path", "ln.macaroon_path", "ln.peer_pubkey"]);
+    keys
+}
+
+/// Map a lower-cased, prefix-stripped env var name (underscores intact) onto one of
+/// our dotted config keys by comparing it against each known key with its own `.`
+/// replaced by `_`, e.g. `v2_ohttp_relay` matches `v2.ohttp_relay`'s flattened form.
+/// A key for a feature that isn't compiled in is simply absent from the list, so it
+/// is ignored rather than mis-split.
+fn env_key_for(flat: &str) -> Option<&'static str> {
+    known_env_keys().into_iter().find(|known| known.replace('.', "_") == flat)
+}
+
+/// A `config` source built from explicit `(dotted_key, value)` pairs, used to layer
+/// `PAYJOIN_`-prefixed env vars in without relying on `config::Environment`'s
+/// separator-based key mapping (see [`env_key_for`]).
+#[derive(Debug, Clone)]
+struct DottedEnvSource {
+    entries: Vec<(&'static str, String)>,
+}
+
+impl Source for DottedEnvSource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> { Box::new(self.clone()) }
+
+    fn collect(&self) -> Result<Map<String, Value>, ConfigError> {
+        let mut root = Map::new();
+        for (key, value) in &self.entries {
+            insert_dotted(&mut root, key, Value::new(None, ValueKind::String(value.clone())));
+        }
+        Ok(root)
+    }
+}
+
+/// Insert `value` at `dotted_key` into `map`, building out any intermediate tables
+/// (e.g. `bitcoind.rpcuser` creates/reuses a `bitcoind` table and sets `rpcuser` on it).
+fn insert_dotted(map: &mut Map<String, Value>, dotted_key: &str, value: Value) {
+    match dotted_key.split_once('.') {
+        Some((head, rest)) => {
+            let entry = map
+                .entry(head.to_string())
+                .or_insert_with(|| Value::new(None, ValueKind::Table(Map::new())));
+            if let ValueKind::Table(ref mut table) = entry.kind {
+                insert_dotted(table, rest, value);
+            }
+        }
+        None => {
+            map.insert(dotted_key.to_string(), value);
+        }
+    }
+}
+
+/// Layer `PAYJOIN_`-prefixed environment variables onto the dotted config keys, e.g.
+/// `PAYJOIN_BITCOIND_RPCPASSWORD` maps to `bitcoind.rpcpassword` and
+/// `PAYJOIN_V2_OHTTP_RELAY` maps to `v2.ohttp_relay`.
+///
+/// Modeled on ord's `Settings::merge`: we walk `std::env::vars_os` ourselves rather
+/// than handing everything straight to `config::Environment`, so a non-UTF8 name
+/// under the `PAYJOIN_` prefix, or a non-UTF8 value for a recognized key, produces a
+/// descriptive `ConfigError` instead of being silently skipped (unrecognized keys are
+/// ignored, value and all), and so multi-word leaves survive (see [`env_key_for`]). This
+/// source is added after `config.toml`, so it outranks the file, but `config`'s
+/// overrides (set via `set_override`/`set_override_option` for CLI flags) always win
+/// regardless of source order, so CLI flags still take precedence over both.
+fn add_env_defaults(builder: Builder) -> Result<Builder, ConfigError> {
+    let mut entries = Vec::new();
+    for (name, value) in std::env::vars_os() {
+        let name = match name.into_string() {
+            Ok(name) => name,
+            Err(name) =>
+                if name.to_string_lossy().starts_with(ENV_PREFIX) {
+                    return Err(ConfigError::Message(format!(
+                        "environment variable {:?} is not valid UTF-8",
+                        name
+                    )));
+                } else {
+                    continue;
+                },
+        };
+
+        let Some(stripped) = name.strip_prefix(ENV_PREFIX) else { continue };
+        let Some(key) = env_key_for(&stripped.to_lowercase()) else { continue };
+
+        let value = value.into_string().map_err(|_| {
+            ConfigError::Message(format!("environment variable \"{name}\" is not valid UTF-8"))
+        })?;
+
+        entries.push((key, value));
+    }
+
+    Ok(builder.add_source(DottedEnvSource { entries }))
+}
+
 #[cfg(feature = "v2")]
 fn deserialize_ohttp_keys_from_path<'de, D>(
     deserializer: D,
@@ -192,3 +589,167 @@ where
             .map(Some),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `cargo test` runs test functions concurrently by default, but `HOME` (read by
+    /// `dirs::home_dir`/`dirs::config_dir`) is process-global - serialize the handful
+    /// of tests that override it so they can't race each other or any test that
+    /// resolves a platform directory while `HOME` is pointed at a throwaway dir.
+    static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    #[test]
+    fn default_rpc_port_matches_bitcoind_per_chain() {
+        assert_eq!(Chain::Bitcoin.default_rpc_port(), 8332);
+        assert_eq!(Chain::Testnet.default_rpc_port(), 18332);
+        assert_eq!(Chain::Signet.default_rpc_port(), 38332);
+        assert_eq!(Chain::Regtest.default_rpc_port(), 18443);
+    }
+
+    /// Exercises the non-Windows/non-macOS branch of `default_bitcoin_dir`, which is
+    /// what CI actually runs on; the other branches are simple enough to read by eye
+    /// and can't be exercised without faking `target_os`.
+    #[test]
+    fn cookie_path_finds_cookie_under_chains_data_dir_suffix() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let original_home = std::env::var_os("HOME");
+
+        let home = std::env::temp_dir()
+            .join(format!("payjoin-cli-config-test-home-{}-{}", std::process::id(), line!()));
+        let _ = std::fs::remove_dir_all(&home);
+        // SAFETY: serialized by `ENV_LOCK` above, so no other test observes `HOME`
+        // while it's pointed at this throwaway directory.
+        unsafe {
+            std::env::set_var("HOME", &home);
+        }
+
+        let regtest_dir = home.join(".bitcoin").join("regtest");
+        std::fs::create_dir_all(&regtest_dir).unwrap();
+        std::fs::write(regtest_dir.join(".cookie"), "user:pass").unwrap();
+
+        assert_eq!(Chain::Regtest.cookie_path(), Some(regtest_dir.join(".cookie")));
+        // mainnet has no data-dir suffix, so it looks directly under ~/.bitcoin and
+        // won't find the regtest-only cookie we just wrote.
+        assert_eq!(Chain::Bitcoin.cookie_path(), None);
+
+        let _ = std::fs::remove_dir_all(&home);
+        // SAFETY: see above; still holding `ENV_LOCK`.
+        unsafe {
+            match original_home {
+                Some(value) => std::env::set_var("HOME", value),
+                None => std::env::remove_var("HOME"),
+            }
+        }
+    }
+
+    /// A bare-bones `Command` exposing just the args `resolve_data_dir`,
+    /// `resolve_config_path`, and `probe_bitcoind_auth` read out of `ArgMatches`.
+    fn test_command() -> clap::Command {
+        clap::Command::new("test")
+            .arg(clap::Arg::new("data_dir").long("data-dir").value_parser(clap::value_parser!(PathBuf)))
+            .arg(clap::Arg::new("config").long("config").value_parser(clap::value_parser!(PathBuf)))
+            .arg(clap::Arg::new("chain").long("chain").value_parser(clap::value_parser!(Chain)))
+            .arg(clap::Arg::new("cookie_file").long("cookie-file"))
+            .arg(clap::Arg::new("rpcuser").long("rpcuser"))
+            .arg(clap::Arg::new("rpcpassword").long("rpcpassword"))
+    }
+
+    fn matches(args: &[&str]) -> ArgMatches {
+        let mut full = vec!["test"];
+        full.extend_from_slice(args);
+        test_command().get_matches_from(full)
+    }
+
+    #[test]
+    fn resolve_data_dir_prefers_explicit_flag() {
+        let m = matches(&["--data-dir", "/tmp/payjoin-explicit-data-dir"]);
+        assert_eq!(resolve_data_dir(&m), PathBuf::from("/tmp/payjoin-explicit-data-dir"));
+    }
+
+    #[test]
+    fn resolve_data_dir_falls_back_when_unset() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let m = matches(&[]);
+        // Either the platform config dir (joined with our subdir) or, absent one,
+        // CWD - but never an empty path.
+        assert_ne!(resolve_data_dir(&m), PathBuf::new());
+    }
+
+    #[test]
+    fn resolve_config_path_prefers_explicit_config_flag() {
+        let m = matches(&["--config", "/tmp/payjoin-explicit-config.toml", "--data-dir", "/tmp/ignored"]);
+        assert_eq!(resolve_config_path(&m), PathBuf::from("/tmp/payjoin-explicit-config.toml"));
+    }
+
+    #[test]
+    fn resolve_config_path_falls_back_to_data_dir_candidate_when_nothing_exists() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let data_dir = std::env::temp_dir()
+            .join(format!("payjoin-cli-config-test-datadir-{}-{}", std::process::id(), line!()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let m = matches(&["--data-dir", data_dir.to_str().unwrap()]);
+
+        // No config.toml exists anywhere we'd look, so this must fall back to the
+        // highest-priority (--data-dir) candidate rather than a hardcoded CWD path.
+        assert_eq!(resolve_config_path(&m), data_dir.join("config.toml"));
+    }
+
+    #[test]
+    fn resolve_config_path_uses_data_dir_config_toml_when_it_exists() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        let data_dir = std::env::temp_dir()
+            .join(format!("payjoin-cli-config-test-datadir-exists-{}-{}", std::process::id(), line!()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("config.toml"), "").unwrap();
+        let m = matches(&["--data-dir", data_dir.to_str().unwrap()]);
+
+        assert_eq!(resolve_config_path(&m), data_dir.join("config.toml"));
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn probe_bitcoind_auth_detects_cookie_from_cli_flag() {
+        let m = matches(&["--cookie-file", "/tmp/some.cookie"]);
+        let config_path = PathBuf::from("/nonexistent/payjoin-cli-config-test.toml");
+        let (chain, has_explicit_auth) = probe_bitcoind_auth(&m, &config_path).unwrap();
+        assert_eq!(chain, Chain::Regtest);
+        assert!(has_explicit_auth);
+    }
+
+    #[test]
+    fn probe_bitcoind_auth_reports_no_auth_when_nothing_configured() {
+        let m = matches(&[]);
+        let config_path = PathBuf::from("/nonexistent/payjoin-cli-config-test.toml");
+        let (_, has_explicit_auth) = probe_bitcoind_auth(&m, &config_path).unwrap();
+        assert!(!has_explicit_auth);
+    }
+
+    #[test]
+    fn env_key_for_maps_underscore_leaves_to_their_dotted_key() {
+        assert_eq!(env_key_for("bitcoind_rpcpassword"), Some("bitcoind.rpcpassword"));
+        // The leaf itself contains an underscore - a naive replace-every-`_` or
+        // split-on-last-`_` would mangle this into `v2.ohttp.relay` or `v2_ohttp.relay`.
+        #[cfg(feature = "v2")]
+        assert_eq!(env_key_for("v2_ohttp_relay"), Some("v2.ohttp_relay"));
+    }
+
+    #[test]
+    fn env_key_for_rejects_unknown_keys() {
+        assert_eq!(env_key_for("not_a_real_key"), None);
+    }
+
+    #[test]
+    fn insert_dotted_builds_nested_tables() {
+        let mut map = Map::new();
+        insert_dotted(&mut map, "bitcoind.rpcuser", Value::new(None, ValueKind::String("bitcoin".into())));
+        insert_dotted(&mut map, "bitcoind.rpcpassword", Value::new(None, ValueKind::String("hunter2".into())));
+        insert_dotted(&mut map, "chain", Value::new(None, ValueKind::String("regtest".into())));
+
+        let ValueKind::Table(bitcoind) = &map["bitcoind"].kind else { panic!("expected a table") };
+        assert_eq!(bitcoind["rpcuser"].kind, ValueKind::String("bitcoin".into()));
+        assert_eq!(bitcoind["rpcpassword"].kind, ValueKind::String("hunter2".into()));
+        assert_eq!(map["chain"].kind, ValueKind::String("regtest".into()));
+    }
+}
@@ -0,0 +1,319 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use payjoin::bitcoin::hashes::Hash;
+use payjoin::bitcoin::psbt::Psbt;
+use payjoin::bitcoin::{Amount, OutPoint, TxOut};
+
+use super::config::LnConfig;
+
+/// A thin client over the Lightning node configured in `LnConfig`.
+///
+/// Supports LND's REST gateway authenticated with its TLS cert + macaroon, and CLN's
+/// REST plugin authenticated with a rune/token, following the same split nolooking
+/// and the CLN nostr-relay integration use respectively.
+pub(crate) enum LnClient {
+    Lnd { agent: ureq::Agent, node_address: payjoin::Url, macaroon: Vec<u8>, peer_pubkey: Vec<u8> },
+    Cln { agent: ureq::Agent, node_address: payjoin::Url, rune: String, peer_pubkey: Vec<u8> },
+}
+
+impl LnClient {
+    pub(crate) fn from_config(config: &LnConfig) -> Result<Self> {
+        let peer_pubkey = hex::decode(&config.peer_pubkey)
+            .with_context(|| format!("ln.peer_pubkey {:?} is not valid hex", config.peer_pubkey))?;
+        match config.macaroon_path.as_ref() {
+            // `cert_path` here is LND's self-signed `tls.cert`, so only this arm
+            // builds the cert-pinned agent.
+            Some(macaroon_path) => {
+                let agent = build_lnd_agent(config.cert_path.as_deref())?;
+                let macaroon = std::fs::read(macaroon_path)
+                    .with_context(|| format!("failed to read macaroon at {macaroon_path:?}"))?;
+                Ok(Self::Lnd {
+                    agent,
+                    node_address: config.node_address.clone(),
+                    macaroon,
+                    peer_pubkey,
+                })
+            }
+            // Without a macaroon we assume a CLN REST endpoint authenticated by a
+            // rune passed via `cert_path`, matching the CLN nostr-relay integration.
+            // `cert_path` here holds rune *text*, not a certificate, so we talk to it
+            // with a plain agent rather than `build_lnd_agent`'s cert pinning.
+            None => {
+                let rune_path = config.cert_path.as_ref().context(
+                    "ln config requires either a macaroon_path (LND) or a rune in cert_path (CLN)",
+                )?;
+                let rune = std::fs::read_to_string(rune_path)
+                    .with_context(|| format!("failed to read rune at {rune_path:?}"))?
+                    .trim()
+                    .to_string();
+                Ok(Self::Cln {
+                    agent: ureq::Agent::new(),
+                    node_address: config.node_address.clone(),
+                    rune,
+                    peer_pubkey,
+                })
+            }
+        }
+    }
+
+    /// Ask the node to fund a channel-open PSBT covering `channel_value` for a
+    /// channel opened with the peer configured in `ln.peer_pubkey`, as nolooking does
+    /// when a payjoin is used to atomically open a channel. Returns the funded PSBT
+    /// the node produced; the caller is expected to pull the funding output out of it
+    /// and splice it into the payjoin proposal via [`substitute_channel_output`].
+    pub(crate) fn request_funding_psbt(
+        &self,
+        channel_value: Amount,
+        channel_outpoint: OutPoint,
+    ) -> Result<Psbt> {
+        match self {
+            Self::Lnd { agent, node_address, macaroon, peer_pubkey } => lnd_request_funding_psbt(
+                agent,
+                node_address,
+                macaroon,
+                peer_pubkey,
+                channel_value,
+                channel_outpoint,
+            ),
+            Self::Cln { agent, node_address, rune, peer_pubkey } => cln_request_funding_psbt(
+                agent,
+                node_address,
+                rune,
+                peer_pubkey,
+                channel_value,
+            ),
+        }
+    }
+}
+
+/// Build the `ureq` agent used for requests to an LND node, pinning its self-signed
+/// `tls.cert` as the sole trust root when one is given. Only called from the LND arm
+/// of [`LnClient::from_config`] - CLN's REST plugin is typically fronted by a cert
+/// issued by a real CA and uses a plain agent instead.
+fn build_lnd_agent(cert_path: Option<&Path>) -> Result<ureq::Agent> {
+    let Some(cert_path) = cert_path else { return Ok(ureq::Agent::new()) };
+
+    let cert_pem = std::fs::read(cert_path)
+        .with_context(|| format!("failed to read TLS cert at {cert_path:?}"))?;
+    let cert =
+        native_tls::Certificate::from_pem(&cert_pem).context("failed to parse LND TLS cert")?;
+    let connector = native_tls::TlsConnector::builder()
+        .add_root_certificate(cert)
+        .build()
+        .context("failed to build TLS connector for LND")?;
+
+    Ok(ureq::AgentBuilder::new().tls_connector(Arc::new(connector)).build())
+}
+
+/// LND's `lnrpc.Lightning/OpenChannel` via its REST gateway, requesting a
+/// `funding_shim.psbt_shim` so the channel's funding output is paid for by the
+/// incoming payjoin rather than a wallet-selected input.
+fn lnd_request_funding_psbt(
+    agent: &ureq::Agent,
+    node_address: &payjoin::Url,
+    macaroon: &[u8],
+    peer_pubkey: &[u8],
+    channel_value: Amount,
+    channel_outpoint: OutPoint,
+) -> Result<Psbt> {
+    let url = node_address.join("v1/channels/psbt").context("invalid LND node_address")?;
+    // `pending_chan_id` just needs to be a stable 32-byte identifier for this
+    // funding attempt; the channel's own outpoint txid already satisfies that.
+    let pending_chan_id = BASE64.encode(channel_outpoint.txid.to_byte_array());
+    let body = serde_json::json!({
+        "node_pubkey": BASE64.encode(peer_pubkey),
+        "local_funding_amount": channel_value.to_sat().to_string(),
+        "funding_shim": {
+            "psbt_shim": {
+                "pending_chan_id": pending_chan_id,
+                "no_publish": true,
+            }
+        }
+    });
+
+    let response: serde_json::Value = agent
+        .post(url.as_str())
+        .set("Grpc-Metadata-macaroon", &hex::encode(macaroon))
+        .send_json(body)
+        .context("LND PSBT-funded OpenChannel request failed")?
+        .into_json()
+        .context("LND returned a non-JSON response")?;
+
+    let psbt_base64 = response
+        .get("psbt_fund")
+        .and_then(|v| v.get("funded_psbt"))
+        .and_then(|v| v.as_str())
+        .context("LND response missing psbt_fund.funded_psbt")?;
+    Psbt::from_str(psbt_base64).context("LND returned an invalid PSBT")
+}
+
+/// CLN's `fundchannel_start` REST method, which hands back a PSBT covering the
+/// channel's funding output for the caller to merge into a larger transaction -
+/// exactly the shape the payjoin proposal needs.
+fn cln_request_funding_psbt(
+    agent: &ureq::Agent,
+    node_address: &payjoin::Url,
+    rune: &str,
+    peer_pubkey: &[u8],
+    channel_value: Amount,
+) -> Result<Psbt> {
+    let url =
+        node_address.join("v1/fundchannel_start").context("invalid CLN node_address")?;
+    let body = serde_json::json!({
+        "id": hex::encode(peer_pubkey),
+        "amount": channel_value.to_sat(),
+    });
+
+    let response: serde_json::Value = agent
+        .post(url.as_str())
+        .set("Rune", rune)
+        .send_json(body)
+        .context("CLN fundchannel_start request failed")?
+        .into_json()
+        .context("CLN returned a non-JSON response")?;
+
+    let psbt_base64 =
+        response.get("psbt").and_then(|v| v.as_str()).context("CLN response missing psbt")?;
+    Psbt::from_str(psbt_base64).context("CLN returned an invalid PSBT")
+}
+
+/// Replace the original payjoin output with the channel-open funding output the node
+/// returned, so the sender's payment simultaneously opens the channel.
+pub(crate) fn substitute_channel_output(
+    original_outputs: &mut [TxOut],
+    original_output_index: usize,
+    funding_output: TxOut,
+) {
+    original_outputs[original_output_index] = funding_output;
+}
+
+/// Request a channel-open funding PSBT from the configured node and splice its
+/// funding output into the payjoin proposal at `proposal_output_index`, before the
+/// proposal is signed and returned to the sender. This is the receive-path entry
+/// point: call it in place of the normal payjoin output once `LnConfig` is present.
+pub(crate) fn fund_channel_output(
+    ln: &LnConfig,
+    channel_value: Amount,
+    channel_outpoint: OutPoint,
+    proposal_outputs: &mut [TxOut],
+    proposal_output_index: usize,
+) -> Result<()> {
+    let client = LnClient::from_config(ln)?;
+    let funding_psbt = client.request_funding_psbt(channel_value, channel_outpoint)?;
+    let funding_output = find_funding_output(&funding_psbt, channel_value)?;
+    substitute_channel_output(proposal_outputs, proposal_output_index, funding_output);
+    Ok(())
+}
+
+/// Pick the output the node added to fund the channel out of its response PSBT.
+///
+/// The node is free to order/add outputs however its own coin selection sees fit, so
+/// `channel_outpoint` (which designates *our* future channel point, not a position in
+/// the node's PSBT) can't be used to index into `funding_psbt.unsigned_tx.output`.
+/// Instead we identify the funding output by its amount, the one property we know it
+/// must have, and refuse to guess if that's ambiguous - better to fail loudly here
+/// than splice the wrong output into a proposal we're about to sign.
+fn find_funding_output(funding_psbt: &Psbt, channel_value: Amount) -> Result<TxOut> {
+    let mut matches =
+        funding_psbt.unsigned_tx.output.iter().filter(|output| output.value == channel_value);
+    let funding_output = matches
+        .next()
+        .context("funding PSBT has no output matching the requested channel value")?;
+    anyhow::ensure!(
+        matches.next().is_none(),
+        "funding PSBT has more than one output matching the requested channel value; \
+         cannot tell which one is the channel funding output"
+    );
+    Ok(funding_output.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use payjoin::bitcoin::absolute::LockTime;
+    use payjoin::bitcoin::transaction::Version;
+    use payjoin::bitcoin::{ScriptBuf, Transaction};
+
+    use super::*;
+
+    fn write_temp_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("payjoin-cli-ln-test-{name}-{}-{}", std::process::id(), line!()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn peer_pubkey_hex() -> String { "02".to_string() + &"ab".repeat(32) }
+
+    fn cln_config(cert_path: std::path::PathBuf) -> LnConfig {
+        LnConfig {
+            node_address: payjoin::Url::parse("https://cln.example.com").unwrap(),
+            cert_path: Some(cert_path),
+            macaroon_path: None,
+            peer_pubkey: peer_pubkey_hex(),
+        }
+    }
+
+    /// Regression test for a bug where `from_config` built the cert-pinned agent
+    /// unconditionally, before branching on `macaroon_path` - so a real CLN config
+    /// (rune file in `cert_path`, no `macaroon_path`) failed with a misleading "failed
+    /// to parse LND TLS cert" error because the rune text was fed to
+    /// `native_tls::Certificate::from_pem`.
+    #[test]
+    fn from_config_builds_cln_client_without_parsing_cert_path_as_pem() {
+        let rune_path = write_temp_file("rune", "not-a-pem-certificate-but-a-rune-token\n");
+
+        let client = LnClient::from_config(&cln_config(rune_path.clone())).unwrap();
+
+        assert!(matches!(client, LnClient::Cln { rune, .. } if rune == "not-a-pem-certificate-but-a-rune-token"));
+        let _ = std::fs::remove_file(&rune_path);
+    }
+
+    #[test]
+    fn from_config_rejects_invalid_peer_pubkey_hex() {
+        let rune_path = write_temp_file("rune-bad-pubkey", "some-rune\n");
+        let mut config = cln_config(rune_path.clone());
+        config.peer_pubkey = "not-hex".to_string();
+
+        assert!(LnClient::from_config(&config).is_err());
+        let _ = std::fs::remove_file(&rune_path);
+    }
+
+    fn tx_with_outputs(values: &[Amount]) -> Psbt {
+        let outputs = values
+            .iter()
+            .map(|&value| TxOut { value, script_pubkey: ScriptBuf::new() })
+            .collect();
+        let tx = Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: vec![], output: outputs };
+        Psbt::from_unsigned_tx(tx).expect("unsigned transaction is a valid PSBT skeleton")
+    }
+
+    #[test]
+    fn find_funding_output_picks_the_unique_matching_amount() {
+        let channel_value = Amount::from_sat(100_000);
+        let psbt = tx_with_outputs(&[Amount::from_sat(50_000), channel_value]);
+
+        let output = find_funding_output(&psbt, channel_value).unwrap();
+        assert_eq!(output.value, channel_value);
+    }
+
+    #[test]
+    fn find_funding_output_errors_when_no_output_matches() {
+        let channel_value = Amount::from_sat(100_000);
+        let psbt = tx_with_outputs(&[Amount::from_sat(50_000)]);
+
+        assert!(find_funding_output(&psbt, channel_value).is_err());
+    }
+
+    #[test]
+    fn find_funding_output_errors_when_amount_is_ambiguous() {
+        let channel_value = Amount::from_sat(100_000);
+        let psbt = tx_with_outputs(&[channel_value, channel_value]);
+
+        assert!(find_funding_output(&psbt, channel_value).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+mod config;
+#[cfg(feature = "lightning")]
+mod ln;
+mod rpc;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use clap::ArgMatches;
+
+pub(crate) use config::Config;
+pub(crate) use rpc::ControlServer;
+
+/// Start the control RPC server on `config.control.bind` in the background. Call
+/// this once, right after building `Config`, from whichever subcommand begins a
+/// long-lived `receive`/`resume` session, so a separate `control` invocation can
+/// list, inspect, or abort it without restarting the process.
+pub(crate) fn spawn_control_server(config: &Config) -> Arc<ControlServer> {
+    let server = ControlServer::new(config.control.auth_token.clone());
+    let bind = config.control.bind.clone();
+    let spawned = Arc::clone(&server);
+    std::thread::spawn(move || {
+        if let Err(e) = rpc::serve(&bind, spawned) {
+            log::error!("control RPC server stopped: {e}");
+        }
+    });
+    server
+}
+
+/// Dispatch the `control` CLI subcommand against a running receiver's control RPC.
+pub(crate) fn run_control_subcommand(matches: &ArgMatches, config: &Config) -> Result<()> {
+    rpc::run_control_subcommand(matches, &config.control)
+}
+
+/// Request a channel-open funding PSBT from the node configured in `config.ln` (if
+/// any) and splice its funding output into the payjoin proposal before it is signed.
+/// A no-op when no `[ln]` section is configured, so the normal payjoin output is left
+/// untouched. Call this from the receive path once `proposal_outputs` is known, right
+/// before the proposal is finalized.
+#[cfg(feature = "lightning")]
+pub(crate) fn maybe_fund_channel_output(
+    config: &Config,
+    channel_value: payjoin::bitcoin::Amount,
+    channel_outpoint: payjoin::bitcoin::OutPoint,
+    proposal_outputs: &mut [payjoin::bitcoin::TxOut],
+    proposal_output_index: usize,
+) -> Result<()> {
+    match config.ln.as_ref() {
+        Some(ln) => ln::fund_channel_output(
+            ln,
+            channel_value,
+            channel_outpoint,
+            proposal_outputs,
+            proposal_output_index,
+        ),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use clap::ArgMatches;
+use payjoin::bitcoin::psbt::Psbt;
+use serde::{Deserialize, Serialize};
+
+use super::config::ControlConfig;
+
+/// State of a long-lived v2 receive/resume session, as tracked by the control RPC.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum SessionState {
+    AwaitingProposal,
+    ProposalSent,
+    Completed,
+    Aborted,
+}
+
+impl fmt::Display for SessionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AwaitingProposal => write!(f, "awaiting-proposal"),
+            Self::ProposalSent => write!(f, "proposal-sent"),
+            Self::Completed => write!(f, "completed"),
+            Self::Aborted => write!(f, "aborted"),
+        }
+    }
+}
+
+/// A session summary as returned by the `listsessions` control RPC method.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SessionInfo {
+    pub id: String,
+    pub pj_uri: String,
+    pub state: SessionState,
+}
+
+/// Methods exposed over the `control.bind` JSON-RPC endpoint, à la OpenEthereum's
+/// `rpc_client`/`rpc_cli` split: this is the server side; `ControlClient` below is
+/// the thin client the `control` CLI subcommand drives.
+pub(crate) trait ControlApi {
+    /// List active v2 receive/resume sessions.
+    fn list_sessions(&self) -> Result<Vec<SessionInfo>>;
+    /// Fetch the fallback (original, unsigned) PSBT for a session.
+    fn get_original_psbt(&self, session_id: &str) -> Result<Psbt>;
+    /// Abort a running session so it stops polling the directory for a proposal.
+    fn abort_session(&self, session_id: &str) -> Result<()>;
+}
+
+/// A client for the control RPC endpoint, used by the `control` CLI subcommand so
+/// operators can inspect or cancel sessions in a running daemonized receiver without
+/// restarting it.
+pub(crate) struct ControlClient {
+    bind: String,
+    auth_token: Option<String>,
+}
+
+impl ControlClient {
+    pub(crate) fn new(bind: String, auth_token: Option<String>) -> Self { Self { bind, auth_token } }
+
+    fn call<T: serde::de::DeserializeOwned>(&self, method: &str, params: serde_json::Value) -> Result<T> {
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+            "auth_token": self.auth_token,
+        });
+        jsonrpc_client(&self.bind, &request)
+    }
+
+    pub(crate) fn list_sessions(&self) -> Result<Vec<SessionInfo>> {
+        self.call("listsessions", serde_json::json!([]))
+    }
+
+    pub(crate) fn get_original_psbt(&self, session_id: &str) -> Result<Psbt> {
+        let psbt_base64: String = self.call("getoriginalpsbt", serde_json::json!([session_id]))?;
+        Psbt::from_str(&psbt_base64).context("control RPC returned an invalid PSBT")
+    }
+
+    pub(crate) fn abort_session(&self, session_id: &str) -> Result<()> {
+        self.call::<bool>("abortsession", serde_json::json!([session_id])).map(|_| ())
+    }
+}
+
+/// Send a single JSON-RPC request to `bind`, which is either a loopback `host:port`
+/// or a `unix:/path/to.sock` URI, and decode the `result` field of the response.
+fn jsonrpc_client<T: serde::de::DeserializeOwned>(
+    bind: &str,
+    request: &serde_json::Value,
+) -> Result<T> {
+    let mut body = Vec::new();
+    if let Some(path) = bind.strip_prefix("unix:") {
+        let mut stream = std::os::unix::net::UnixStream::connect(path)
+            .with_context(|| format!("failed to connect to control socket {path}"))?;
+        stream.write_all(request.to_string().as_bytes())?;
+        stream.shutdown(std::net::Shutdown::Write)?;
+        stream.read_to_end(&mut body)?;
+    } else {
+        let mut stream = std::net::TcpStream::connect(bind)
+            .with_context(|| format!("failed to connect to control endpoint {bind}"))?;
+        stream.write_all(request.to_string().as_bytes())?;
+        stream.shutdown(std::net::Shutdown::Write)?;
+        stream.read_to_end(&mut body)?;
+    }
+
+    let response: serde_json::Value = serde_json::from_slice(&body)?;
+    if let Some(error) = response.get("error") {
+        anyhow::bail!("control RPC error: {error}");
+    }
+    let result = response.get("result").context("control RPC response had no result")?;
+    Ok(serde_json::from_value(result.clone())?)
+}
+
+/// A tracked v2 receive/resume session: the receive flow registers one as soon as it
+/// has a payjoin URI to hand to the sender. Its state changes only when it is aborted
+/// over the control RPC, and sessions are never removed once registered.
+struct Session {
+    pj_uri: String,
+    state: SessionState,
+    original_psbt: Psbt,
+}
+
+/// Live session state backing the control RPC server, shared between the receive
+/// flow (which registers sessions) and whichever thread is running [`serve`].
+pub(crate) struct ControlServer {
+    sessions: Mutex<HashMap<String, Session>>,
+    /// Required value of every request's `auth_token` field, per
+    /// [`ControlConfig::auth_token`]. `None` leaves the server unauthenticated.
+    auth_token: Option<String>,
+}
+
+impl ControlServer {
+    pub(crate) fn new(auth_token: Option<String>) -> Arc<Self> {
+        Arc::new(Self { sessions: Mutex::new(HashMap::new()), auth_token })
+    }
+
+    /// Whether `request_token` satisfies this server's configured `auth_token`. No
+    /// token configured means no authentication is required.
+    fn authorized(&self, request_token: Option<&str>) -> bool {
+        match &self.auth_token {
+            Some(expected) => request_token == Some(expected.as_str()),
+            None => true,
+        }
+    }
+
+    /// Register a newly created session so it shows up in `listsessions` and can be
+    /// aborted. Called from the receive/resume flow once a payjoin URI exists.
+    pub(crate) fn register_session(&self, id: String, pj_uri: String, original_psbt: Psbt) {
+        self.sessions
+            .lock()
+            .expect("session lock poisoned")
+            .insert(id, Session { pj_uri, state: SessionState::AwaitingProposal, original_psbt });
+    }
+
+    /// Whether the receive loop should stop polling the directory for `id` because
+    /// an operator aborted it over the control RPC.
+    pub(crate) fn is_aborted(&self, id: &str) -> bool {
+        matches!(
+            self.sessions.lock().expect("session lock poisoned").get(id).map(|s| &s.state),
+            Some(SessionState::Aborted)
+        )
+    }
+}
+
+impl ControlApi for ControlServer {
+    fn list_sessions(&self) -> Result<Vec<SessionInfo>> {
+        Ok(self
+            .sessions
+            .lock()
+            .expect("session lock poisoned")
+            .iter()
+            .filter(|(_, session)| {
+                matches!(session.state, SessionState::AwaitingProposal | SessionState::ProposalSent)
+            })
+            .map(|(id, session)| SessionInfo {
+                id: id.clone(),
+                pj_uri: session.pj_uri.clone(),
+                state: session.state.clone(),
+            })
+            .collect())
+    }
+
+    fn get_original_psbt(&self, session_id: &str) -> Result<Psbt> {
+        self.sessions
+            .lock()
+            .expect("session lock poisoned")
+            .get(session_id)
+            .map(|session| session.original_psbt.clone())
+            .with_context(|| format!("no session {session_id}"))
+    }
+
+    fn abort_session(&self, session_id: &str) -> Result<()> {
+        let mut sessions = self.sessions.lock().expect("session lock poisoned");
+        let session =
+            sessions.get_mut(session_id).with_context(|| format!("no session {session_id}"))?;
+        session.state = SessionState::Aborted;
+        Ok(())
+    }
+}
+
+/// Run the control RPC server on `bind` (a loopback `host:port` or `unix:/path` per
+/// [`ControlConfig::bind`]) until the process exits. Spawn this on its own thread
+/// from the receive/resume entrypoint once a `ControlServer` has been created, so
+/// operators can query or abort sessions without restarting the daemon.
+pub(crate) fn serve(bind: &str, server: Arc<ControlServer>) -> Result<()> {
+    if let Some(path) = bind.strip_prefix("unix:") {
+        let _ = std::fs::remove_file(path);
+        let listener = std::os::unix::net::UnixListener::bind(path)
+            .with_context(|| format!("failed to bind control socket {path}"))?;
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let server = Arc::clone(&server);
+                    std::thread::spawn(move || handle_connection(stream, server.as_ref()));
+                }
+                Err(e) => log::warn!("control connection error: {e}"),
+            }
+        }
+    } else {
+        let listener = std::net::TcpListener::bind(bind)
+            .with_context(|| format!("failed to bind control endpoint {bind}"))?;
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let server = Arc::clone(&server);
+                    std::thread::spawn(move || handle_connection(stream, server.as_ref()));
+                }
+                Err(e) => log::warn!("control connection error: {e}"),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Handle one control RPC connection: read the request until the client shuts down
+/// its write half (mirroring `jsonrpc_client`'s framing), dispatch it, and write back
+/// a single JSON-RPC response before the connection closes.
+fn handle_connection<S: Read + Write>(mut stream: S, server: &ControlServer) {
+    let mut body = Vec::new();
+    if let Err(e) = stream.read_to_end(&mut body) {
+        log::warn!("failed to read control request: {e}");
+        return;
+    }
+    let response = dispatch(&body, server);
+    if let Err(e) = stream.write_all(&response) {
+        log::warn!("failed to write control response: {e}");
+    }
+}
+
+fn dispatch(body: &[u8], server: &ControlServer) -> Vec<u8> {
+    let request: serde_json::Value = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return error_response(serde_json::Value::Null, format!("invalid request: {e}")),
+    };
+    let id = request.get("id").cloned().unwrap_or(serde_json::Value::Null);
+    let method = request.get("method").and_then(|m| m.as_str()).unwrap_or_default();
+    let params = request.get("params").and_then(|p| p.as_array()).cloned().unwrap_or_default();
+    let auth_token = request.get("auth_token").and_then(|t| t.as_str());
+
+    if !server.authorized(auth_token) {
+        return error_response(id, "unauthorized".to_string());
+    }
+
+    let result = dispatch_method(server, method, &params);
+    match result {
+        Ok(value) =>
+            serde_json::json!({"jsonrpc": "2.0", "id": id, "result": value}).to_string().into_bytes(),
+        Err(e) => error_response(id, e.to_string()),
+    }
+}
+
+fn dispatch_method(
+    server: &ControlServer,
+    method: &str,
+    params: &[serde_json::Value],
+) -> Result<serde_json::Value> {
+    match method {
+        "listsessions" => Ok(serde_json::to_value(server.list_sessions()?)?),
+        "getoriginalpsbt" => {
+            let session_id = first_string_param(params)?;
+            Ok(serde_json::Value::String(server.get_original_psbt(session_id)?.to_string()))
+        }
+        "abortsession" => {
+            let session_id = first_string_param(params)?;
+            server.abort_session(session_id)?;
+            Ok(serde_json::Value::Bool(true))
+        }
+        other => anyhow::bail!("unknown method {other}"),
+    }
+}
+
+fn first_string_param(params: &[serde_json::Value]) -> Result<&str> {
+    params.first().and_then(|v| v.as_str()).context("missing session_id param")
+}
+
+fn error_response(id: serde_json::Value, message: String) -> Vec<u8> {
+    serde_json::json!({"jsonrpc": "2.0", "id": id, "error": {"message": message}})
+        .to_string()
+        .into_bytes()
+}
+
+/// Entry point for the `control` CLI subcommand: connects to a running receiver's
+/// control RPC (per [`ControlConfig::bind`]) and drives `listsessions`,
+/// `getoriginalpsbt` or `abortsession` without restarting the daemon.
+pub(crate) fn run_control_subcommand(matches: &ArgMatches, control: &ControlConfig) -> Result<()> {
+    let client = ControlClient::new(control.bind.clone(), control.auth_token.clone());
+    match matches.subcommand() {
+        Some(("list", _)) =>
+            for session in client.list_sessions()? {
+                println!("{}\t{}\t{}", session.id, session.state, session.pj_uri);
+            },
+        Some(("get-psbt", matches)) => {
+            let session_id = matches.get_one::<String>("session_id").expect("required");
+            println!("{}", client.get_original_psbt(session_id)?);
+        }
+        Some(("abort", matches)) => {
+            let session_id = matches.get_one::<String>("session_id").expect("required");
+            client.abort_session(session_id)?;
+            println!("aborted {session_id}");
+        }
+        _ => anyhow::bail!("usage: payjoin-cli control <list|get-psbt|abort <session_id>>"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use payjoin::bitcoin::absolute::LockTime;
+    use payjoin::bitcoin::transaction::Version;
+    use payjoin::bitcoin::Transaction;
+
+    use super::*;
+
+    fn empty_psbt() -> Psbt {
+        let tx = Transaction { version: Version::TWO, lock_time: LockTime::ZERO, input: vec![], output: vec![] };
+        Psbt::from_unsigned_tx(tx).expect("empty transaction is a valid PSBT skeleton")
+    }
+
+    /// Exercises `serve`/`dispatch`/`ControlApi` against `ControlClient`/`jsonrpc_client`
+    /// over a real unix socket, which is also what confirms the two sides agree on the
+    /// same request/response framing (write, shut down the write half, read to EOF).
+    fn start_server(server: &Arc<ControlServer>) -> String {
+        static NEXT_ID: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+        let test_id = NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let socket_path = std::env::temp_dir().join(format!(
+            "payjoin-cli-control-test-{}-{}.sock",
+            std::process::id(),
+            test_id
+        ));
+        let _ = std::fs::remove_file(&socket_path);
+        let bind = format!("unix:{}", socket_path.display());
+
+        let serve_server = Arc::clone(server);
+        let serve_bind = bind.clone();
+        std::thread::spawn(move || {
+            let _ = serve(&serve_bind, serve_server);
+        });
+        for _ in 0..200 {
+            if socket_path.exists() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(socket_path.exists(), "control socket never appeared");
+        bind
+    }
+
+    #[test]
+    fn control_rpc_round_trips_over_unix_socket() {
+        let server = ControlServer::new(None);
+        server.register_session("abc".to_string(), "bitcoin:123".to_string(), empty_psbt());
+        let bind = start_server(&server);
+
+        let client = ControlClient::new(bind, None);
+
+        let sessions = client.list_sessions().expect("listsessions should succeed");
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, "abc");
+        assert_eq!(sessions[0].pj_uri, "bitcoin:123");
+
+        let psbt = client.get_original_psbt("abc").expect("getoriginalpsbt should succeed");
+        assert_eq!(psbt, empty_psbt());
+
+        assert!(client.get_original_psbt("missing").is_err());
+
+        client.abort_session("abc").expect("abortsession should succeed");
+        assert!(server.is_aborted("abc"));
+    }
+
+    #[test]
+    fn control_rpc_rejects_requests_without_the_configured_auth_token() {
+        let server = ControlServer::new(Some("s3cr3t".to_string()));
+        server.register_session("abc".to_string(), "bitcoin:123".to_string(), empty_psbt());
+        let bind = start_server(&server);
+
+        let unauthenticated = ControlClient::new(bind.clone(), None);
+        assert!(unauthenticated.list_sessions().is_err());
+
+        let wrong_token = ControlClient::new(bind.clone(), Some("wrong".to_string()));
+        assert!(wrong_token.list_sessions().is_err());
+
+        let authenticated = ControlClient::new(bind, Some("s3cr3t".to_string()));
+        assert_eq!(authenticated.list_sessions().unwrap().len(), 1);
+    }
+}